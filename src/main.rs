@@ -1,83 +1,525 @@
 use clap::{command, value_parser, Arg};
+use gilrs::ff::{BaseEffect, BaseEffectType, EffectBuilder, Replay, Ticks};
 use gilrs::{Axis, Event, Gilrs};
 use rdev::{simulate, EventType, Key};
+use serde::de::Error as _;
 use serde::Deserialize;
 use serde_json::from_reader;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Default `alone_timeout_millis` when a dual-function button omits it,
+/// matching xremap's own default for `held`/`alone` remaps.
+const DEFAULT_ALONE_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Auto-repeat settings for a mapping, porting xdl's `KeyRepeatConfig`: once a
+/// key has been down for `first` millis, it re-fires every `multi` millis for
+/// as long as it stays down. Mappings without a `repeat` section never repeat.
+#[derive(Deserialize, Clone, Copy)]
+struct RepeatCfg {
+    first: u64,
+    multi: u64,
+}
+
+/// Tracks when a repeating key should next re-fire.
+struct RepeatSchedule {
+    next_fire: SystemTime,
+    multi: Duration,
+}
+
+/// A rumble/force-feedback pulse to play when a mapping's key is pressed.
+#[derive(Deserialize, Clone, Copy)]
+struct RumbleCfg {
+    /// Motor strength, 0-65535.
+    strength: u16,
+    duration_millis: u32,
+}
+
+/// Pre-built force-feedback effects, one per `(gamepad, strength, duration)`
+/// combination referenced by the config, so `handle_event` only ever has to
+/// play an existing effect instead of building one on the hot path.
+type RumbleEffects = HashMap<(gilrs::GamepadId, u16, u32), gilrs::ff::Effect>;
 
-#[derive(Deserialize)]
 struct AxisCfg {
     axis: gilrs::Axis,
     high_key: Key,
     low_key: Key,
-    threshold: f32,
+    high_threshold: f32,
+    low_threshold: f32,
+    /// Ignore raw values below this magnitude, like gilrs's `DEFAULT_DEADZONE`.
+    deadzone: Option<f32>,
+    /// Flips the sign of the raw value before thresholding.
+    invert: bool,
+    /// Response curve applied to the raw value after the deadzone and
+    /// inversion, before it's compared against the thresholds.
+    curve: ResponseCurve,
+    repeat: Option<RepeatCfg>,
+    /// Rumble to play on the gamepad when `high_key`/`low_key` is first pressed.
+    rumble: Option<RumbleCfg>,
 }
 
+/// Mirrors `AxisCfg`'s JSON shape, but keeps `high_threshold`/`low_threshold`
+/// optional alongside the older single `threshold` field so configs written
+/// before the two were split still parse.
 #[derive(Deserialize)]
+struct AxisCfgData {
+    axis: gilrs::Axis,
+    high_key: Key,
+    low_key: Key,
+    /// Deprecated: applies to both directions when `high_threshold`/
+    /// `low_threshold` aren't set. Kept only for backward compatibility.
+    threshold: Option<f32>,
+    high_threshold: Option<f32>,
+    low_threshold: Option<f32>,
+    deadzone: Option<f32>,
+    #[serde(default)]
+    invert: bool,
+    #[serde(default)]
+    curve: ResponseCurve,
+    repeat: Option<RepeatCfg>,
+    rumble: Option<RumbleCfg>,
+}
+
+impl<'de> Deserialize<'de> for AxisCfg {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let data = AxisCfgData::deserialize(deserializer)?;
+        let high_threshold = data.high_threshold.or(data.threshold).ok_or_else(|| {
+            D::Error::custom("axis mapping needs `high_threshold` (or the deprecated `threshold`)")
+        })?;
+        let low_threshold = data.low_threshold.or(data.threshold).ok_or_else(|| {
+            D::Error::custom("axis mapping needs `low_threshold` (or the deprecated `threshold`)")
+        })?;
+        Ok(AxisCfg {
+            axis: data.axis,
+            high_key: data.high_key,
+            low_key: data.low_key,
+            high_threshold,
+            low_threshold,
+            deadzone: data.deadzone,
+            invert: data.invert,
+            curve: data.curve,
+            repeat: data.repeat,
+            rumble: data.rumble,
+        })
+    }
+}
+
+/// Default deadzone magnitude for an axis that doesn't set one, matching
+/// gilrs's own `DEFAULT_DEADZONE`.
+const DEFAULT_DEADZONE: f32 = 0.1;
+
+/// Shapes how a raw axis value feels once converted to a digital keypress.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ResponseCurve {
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+impl ResponseCurve {
+    fn apply(self, value: f32) -> f32 {
+        match self {
+            ResponseCurve::Linear => value,
+            ResponseCurve::Quadratic => value.signum() * value * value,
+        }
+    }
+}
+
+/// A button's key mapping: either a single key, one chord of keys pressed
+/// together (e.g. `["ControlLeft", "KeyX"]`), or an ordered macro of chords
+/// (e.g. `[["ControlLeft", "KeyX"], ["ControlLeft", "KeyC"]]` for Ctrl-X then Ctrl-C).
+#[derive(Clone)]
+enum KeySequence {
+    Single(Key),
+    Chord(Vec<Key>),
+    Macro(Vec<Vec<Key>>),
+}
+
+/// Mirrors `KeySequence`'s JSON shape prior to the non-empty validation
+/// `KeySequence`'s own `Deserialize` impl applies below.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeySequenceData {
+    Single(Key),
+    Chord(Vec<Key>),
+    Macro(Vec<Vec<Key>>),
+}
+
+impl<'de> Deserialize<'de> for KeySequence {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match KeySequenceData::deserialize(deserializer)? {
+            KeySequenceData::Single(key) => Ok(KeySequence::Single(key)),
+            KeySequenceData::Chord(keys) if keys.is_empty() => Err(D::Error::custom(
+                "`key` chord must contain at least one key",
+            )),
+            KeySequenceData::Chord(keys) => Ok(KeySequence::Chord(keys)),
+            KeySequenceData::Macro(groups)
+                if groups.is_empty() || groups.iter().any(|group| group.is_empty()) =>
+            {
+                Err(D::Error::custom(
+                    "`key` macro must contain at least one group, and each group at least one key",
+                ))
+            }
+            KeySequenceData::Macro(groups) => Ok(KeySequence::Macro(groups)),
+        }
+    }
+}
+
+impl KeySequence {
+    /// Normalizes any shape into an ordered list of chord groups.
+    fn groups(&self) -> Vec<Vec<Key>> {
+        match self {
+            KeySequence::Single(key) => vec![vec![*key]],
+            KeySequence::Chord(keys) => vec![keys.clone()],
+            KeySequence::Macro(groups) => groups.clone(),
+        }
+    }
+
+    /// `Single`/`Chord` hold their keys down for as long as the button stays
+    /// held; `Macro` instead plays each group out as a discrete tap, in
+    /// order, on a single button press.
+    fn is_macro(&self) -> bool {
+        matches!(self, KeySequence::Macro(_))
+    }
+}
+
+#[derive(Deserialize, Clone)]
 struct ButtonCfg {
     button: gilrs::Button,
-    key: Key,
+    key: KeySequence,
+    /// Key to hold down once the button has been held past `alone_timeout_millis`.
+    /// When set, the button becomes a tap-vs-hold dual-function mapping.
+    held_key: Option<Key>,
+    /// Key to tap when the button is released before the timeout. Defaults to
+    /// the first key of `key`.
+    alone_key: Option<Key>,
+    /// How long the button must be held before it counts as "held" rather than "tapped".
+    alone_timeout_millis: Option<u64>,
+    /// Re-fires `key` at a steady rate for as long as the button stays held.
+    /// Has no effect on macro or tap-vs-hold (`held_key`) mappings.
+    repeat: Option<RepeatCfg>,
+    /// Rumble to play on the gamepad when `key` is first pressed.
+    rumble: Option<RumbleCfg>,
+}
+
+/// A common gamepad family, used to seed sane D-pad default mappings so a
+/// config doesn't need to spell out `DPadUp`/`DPadDown`/`DPadLeft`/`DPadRight`
+/// by hand. `canonicalize` is a hook for vendor-specific face-button
+/// normalization (e.g. Nintendo's rotated A/B and X/Y), but is currently a
+/// no-op for every preset: landing a real swap needs confirming against
+/// actual gilrs output for that vendor's pad first.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Preset {
+    Xbox,
+    Playstation,
+    Nintendo,
+}
+
+impl Preset {
+    /// The D-pad is unambiguous across vendors, so every preset defaults it to
+    /// the arrow keys. Sticks and face buttons are too mapping-specific to
+    /// guess and are left for the user to configure explicitly.
+    fn default_buttons(self) -> Vec<ButtonCfg> {
+        use gilrs::Button::{DPadDown, DPadLeft, DPadRight, DPadUp};
+
+        [
+            (DPadUp, Key::UpArrow),
+            (DPadDown, Key::DownArrow),
+            (DPadLeft, Key::LeftArrow),
+            (DPadRight, Key::RightArrow),
+        ]
+        .into_iter()
+        .map(|(button, key)| ButtonCfg {
+            button,
+            key: KeySequence::Single(key),
+            held_key: None,
+            alone_key: None,
+            alone_timeout_millis: None,
+            repeat: None,
+            rumble: None,
+        })
+        .collect()
+    }
+
+    /// Maps a physical face button to the `South`/`East`/`North`/`West` role
+    /// a `buttons` config is written against. gilrs already resolves each pad
+    /// to these roles via its own SDL-derived gamepad database, so today this
+    /// is the identity mapping for every preset. A vendor-specific swap (e.g.
+    /// Nintendo's physically rotated A/B and X/Y) would need confirming
+    /// against real gilrs output for that pad before it's safe to apply here.
+    fn canonicalize(self, button: gilrs::Button) -> gilrs::Button {
+        match self {
+            Preset::Xbox | Preset::Playstation | Preset::Nintendo => button,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct ControllerCfg {
+    /// Matches against `gilrs::Gamepad::name()`. A controller entry matches a
+    /// gamepad when either its `name` or its `uuid` matches; the first
+    /// matching entry in `controllers` is used.
+    name: Option<String>,
+    /// Matches against `gilrs::Gamepad::uuid()`, written as a lowercase hex string.
+    uuid: Option<String>,
+    /// Seeds default D-pad mappings for this pad family. Face buttons are not
+    /// remapped: `Preset::canonicalize` returns every button unchanged.
+    preset: Option<Preset>,
     axes: Vec<AxisCfg>,
     buttons: Vec<ButtonCfg>,
 }
 
 #[derive(Deserialize)]
 struct Config {
+    /// Gates the rumble/force-feedback subsystem so setups on platforms
+    /// without FF support are unaffected unless they opt in.
+    #[serde(default)]
+    feedback_enabled: bool,
     controllers: Vec<ControllerCfg>,
 }
 
+/// A button press that is waiting to find out whether it will turn out to be
+/// a tap or a hold. Keyed by `(gamepad_id, button)` so that only the
+/// controller owning the gamepad that raised it is ever consulted on expiry.
+struct PendingTap {
+    gamepad_id: gilrs::GamepadId,
+    button: gilrs::Button,
+    deadline: SystemTime,
+}
+
+/// Everything that changes while the main loop runs, bundled so the
+/// `Config`/`ControllerCfg`/`AxisCfg`/`ButtonCfg` call chain can thread one
+/// `&mut` through instead of one parameter per piece of state.
+struct RuntimeState {
+    key_state: HashMap<Key, bool>,
+    pending_taps: HashMap<(gilrs::GamepadId, gilrs::Button), PendingTap>,
+    repeat_state: HashMap<Key, RepeatSchedule>,
+    rumble_effects: RumbleEffects,
+}
+
+impl RuntimeState {
+    fn new(rumble_effects: RumbleEffects) -> Self {
+        RuntimeState {
+            key_state: HashMap::new(),
+            pending_taps: HashMap::new(),
+            repeat_state: HashMap::new(),
+            rumble_effects,
+        }
+    }
+}
+
 impl AxisCfg {
+    /// Normalizes a raw axis value through deadzone, inversion, and the
+    /// configured response curve, in that order.
+    fn normalize(&self, raw_value: f32) -> f32 {
+        let deadzone = self.deadzone.unwrap_or(DEFAULT_DEADZONE);
+        let value = if raw_value.abs() < deadzone {
+            0.0
+        } else {
+            raw_value
+        };
+        let value = if self.invert { -value } else { value };
+        self.curve.apply(value)
+    }
+
     pub fn handle_event(
         &self,
         axis: Axis,
         axis_value: f32,
-        key_state: &mut HashMap<Key, bool>,
+        state: &mut RuntimeState,
+        gamepad_id: gilrs::GamepadId,
+        now: SystemTime,
         verbose: bool,
     ) {
         if axis != self.axis {
             return;
         }
 
-        match axis_value {
-            _ if axis_value < -self.threshold => key_press_once(key_state, self.low_key, verbose),
-            _ if axis_value > self.threshold => key_press_once(key_state, self.high_key, verbose),
+        let value = self.normalize(axis_value);
+
+        match value {
+            _ if value < -self.low_threshold => {
+                if key_press_once(&mut state.key_state, self.low_key, verbose) {
+                    play_rumble(&state.rumble_effects, gamepad_id, self.rumble);
+                }
+                schedule_repeat(&mut state.repeat_state, self.repeat, self.low_key, now);
+            }
+            _ if value > self.high_threshold => {
+                if key_press_once(&mut state.key_state, self.high_key, verbose) {
+                    play_rumble(&state.rumble_effects, gamepad_id, self.rumble);
+                }
+                schedule_repeat(&mut state.repeat_state, self.repeat, self.high_key, now);
+            }
             _ => {
-                key_release_once(key_state, self.low_key, verbose);
-                key_release_once(key_state, self.high_key, verbose);
+                key_release_once(&mut state.key_state, self.low_key, verbose);
+                key_release_once(&mut state.key_state, self.high_key, verbose);
+                state.repeat_state.remove(&self.low_key);
+                state.repeat_state.remove(&self.high_key);
+            }
+        }
+    }
+}
+
+impl ButtonCfg {
+    fn is_dual_function(&self) -> bool {
+        self.held_key.is_some()
+    }
+
+    fn alone_key(&self) -> Key {
+        self.alone_key.unwrap_or_else(|| {
+            self.key
+                .groups()
+                .into_iter()
+                .flatten()
+                .next()
+                // `KeySequence`'s `Deserialize` impl rejects empty chords/macros,
+                // so every successfully-parsed `key` has at least one key.
+                .expect("key sequence must contain at least one key")
+        })
+    }
+
+    fn alone_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(
+            self.alone_timeout_millis
+                .unwrap_or(DEFAULT_ALONE_TIMEOUT_MILLIS),
+        )
+    }
+
+    /// Converts a still-held pending tap into a held-key press once its timeout
+    /// has elapsed. No-op if the button is not this mapping's button.
+    fn fire_pending(&self, pending: &PendingTap, state: &mut RuntimeState, verbose: bool) {
+        if pending.button != self.button {
+            return;
+        }
+        if let Some(held_key) = self.held_key {
+            if key_press_once(&mut state.key_state, held_key, verbose) {
+                play_rumble(&state.rumble_effects, pending.gamepad_id, self.rumble);
             }
         }
     }
 }
 
 impl ControllerCfg {
-    pub fn handle_event(&self, event: Event, key_state: &mut HashMap<Key, bool>, verbose: bool) {
+    fn has_identifier(&self) -> bool {
+        self.name.is_some() || self.uuid.is_some()
+    }
+
+    fn matches(&self, pad: &gilrs::Gamepad<'_>) -> bool {
+        if let Some(name) = &self.name {
+            if name == pad.name() {
+                return true;
+            }
+        }
+        if let Some(uuid) = &self.uuid {
+            let pad_uuid: String = pad.uuid().iter().map(|b| format!("{b:02x}")).collect();
+            if uuid.eq_ignore_ascii_case(&pad_uuid) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Maps a physical button to the vendor-neutral identity `buttons`
+    /// entries are written against, via the preset's `canonicalize` hook
+    /// (currently a no-op; see `Preset::canonicalize`).
+    fn canonical_button(&self, button: gilrs::Button) -> gilrs::Button {
+        self.preset
+            .map(|preset| preset.canonicalize(button))
+            .unwrap_or(button)
+    }
+
+    /// Looks up the mapping for a canonicalized `button`, falling back to the
+    /// preset's defaults when the user hasn't mapped that button explicitly.
+    fn find_button(&self, button: gilrs::Button) -> Option<ButtonCfg> {
+        if let Some(mapping) = self.buttons.iter().find(|m| m.button == button) {
+            return Some(mapping.clone());
+        }
+        self.preset?
+            .default_buttons()
+            .into_iter()
+            .find(|m| m.button == button)
+    }
+
+    pub fn handle_event(&self, event: Event, state: &mut RuntimeState, verbose: bool) {
         match event.event {
             gilrs::EventType::AxisChanged(axis, axis_value, _) => {
                 for axis_mapping in &self.axes {
-                    axis_mapping.handle_event(axis, axis_value, key_state, verbose);
+                    axis_mapping
+                        .handle_event(axis, axis_value, state, event.id, event.time, verbose);
                 }
             }
-            gilrs::EventType::ButtonPressed(button, _) => {
-                if let Some(mapping) = self.buttons.iter().find(|m| m.button == button) {
-                    let _ = simulate(&EventType::KeyPress(mapping.key));
-                    if verbose {
-                        println!("\nSimulated key press {:?}", mapping.key);
+            gilrs::EventType::ButtonPressed(raw_button, _) => {
+                let button = self.canonical_button(raw_button);
+                if let Some(mapping) = self.find_button(button) {
+                    if mapping.is_dual_function() {
+                        state.pending_taps.insert(
+                            (event.id, button),
+                            PendingTap {
+                                gamepad_id: event.id,
+                                button,
+                                deadline: event.time + mapping.alone_timeout(),
+                            },
+                        );
+                    } else if mapping.key.is_macro() {
+                        simulate_macro(&mapping.key.groups(), verbose);
+                        play_rumble(&state.rumble_effects, event.id, mapping.rumble);
+                    } else {
+                        let mut pressed = false;
+                        for key in mapping.key.groups().into_iter().flatten() {
+                            if key_press_once(&mut state.key_state, key, verbose) {
+                                pressed = true;
+                            }
+                            schedule_repeat(
+                                &mut state.repeat_state,
+                                mapping.repeat,
+                                key,
+                                event.time,
+                            );
+                        }
+                        if pressed {
+                            play_rumble(&state.rumble_effects, event.id, mapping.rumble);
+                        }
                     }
                 }
             }
             //gilrs::EventType::ButtonRepeated(button, _) => todo!(),
-            gilrs::EventType::ButtonReleased(button, _) => {
-                if let Some(mapping) = self.buttons.iter().find(|m| m.button == button) {
-                    let _ = simulate(&EventType::KeyRelease(mapping.key));
-                    if verbose {
-                        println!("\nSimulated key release {:?}", mapping.key);
+            gilrs::EventType::ButtonReleased(raw_button, _) => {
+                let button = self.canonical_button(raw_button);
+                if let Some(mapping) = self.find_button(button) {
+                    if mapping.is_dual_function() {
+                        if state.pending_taps.remove(&(event.id, button)).is_some() {
+                            // Released before the timeout converted it to a hold: it was a tap.
+                            let alone_key = mapping.alone_key();
+                            let _ = simulate(&EventType::KeyPress(alone_key));
+                            let _ = simulate(&EventType::KeyRelease(alone_key));
+                            if verbose {
+                                println!("\nSimulated key tap {:?}", alone_key);
+                            }
+                            play_rumble(&state.rumble_effects, event.id, mapping.rumble);
+                        } else if let Some(held_key) = mapping.held_key {
+                            key_release_once(&mut state.key_state, held_key, verbose);
+                        }
+                    } else if mapping.key.is_macro() {
+                        // The macro already played out fully on press; nothing to release.
+                    } else {
+                        let mut keys: Vec<Key> =
+                            mapping.key.groups().into_iter().flatten().collect();
+                        keys.reverse();
+                        for key in keys {
+                            key_release_once(&mut state.key_state, key, verbose);
+                            state.repeat_state.remove(&key);
+                        }
                     }
                 }
             }
@@ -91,14 +533,163 @@ impl Config {
     pub fn handle_event(
         &self,
         event: Event,
-        key_state: &mut HashMap<Key, bool>,
-        gamepad_idx: usize,
+        state: &mut RuntimeState,
+        gilrs: &Gilrs,
         verbose: bool,
     ) {
-        if let Some(mapping) = self.controllers.get(gamepad_idx) {
-            mapping.handle_event(event, key_state, verbose);
+        self.flush_expired_taps(event.time, state, gilrs, verbose);
+        self.flush_due_repeats(event.time, state, verbose);
+        if let Some(mapping) = self.resolve_controller(gilrs, event.id) {
+            mapping.handle_event(event, state, verbose);
         };
     }
+
+    /// Picks the `ControllerCfg` for the gamepad that raised `id`. Controllers
+    /// with a `name`/`uuid` are matched against the live gamepad so profiles
+    /// stay correct across reconnects and hotplug order changes. Controllers
+    /// with neither fall back to the legacy connection-order index, counted
+    /// only among gamepads that no identified controller already claimed —
+    /// so pinning one controller by name/uuid doesn't stop a second,
+    /// un-identified controller entry from still resolving by index.
+    fn resolve_controller(&self, gilrs: &Gilrs, id: gilrs::GamepadId) -> Option<&ControllerCfg> {
+        let pad = gilrs.gamepad(id);
+        if let Some(mapping) = self.controllers.iter().find(|c| c.matches(&pad)) {
+            return Some(mapping);
+        }
+
+        let unclaimed_idx = gilrs
+            .gamepads()
+            .filter(|(_, gp)| {
+                !self
+                    .controllers
+                    .iter()
+                    .any(|c| c.has_identifier() && c.matches(gp))
+            })
+            .position(|(gid, _)| gid == id)?;
+
+        self.controllers
+            .iter()
+            .filter(|c| !c.has_identifier())
+            .nth(unclaimed_idx)
+    }
+
+    /// Builds one force-feedback effect per `(gamepad, strength, duration)`
+    /// combination referenced by mappings on currently-connected gamepads.
+    /// No-op when `feedback_enabled` is false, so platforms without FF
+    /// support are unaffected unless a config opts in.
+    fn build_rumble_effects(&self, gilrs: &mut Gilrs) -> RumbleEffects {
+        let mut effects = RumbleEffects::new();
+        let ids: Vec<gilrs::GamepadId> = gilrs.gamepads().map(|(id, _)| id).collect();
+        for id in ids {
+            self.extend_rumble_effects(gilrs, id, &mut effects);
+        }
+        effects
+    }
+
+    /// Builds the force-feedback effects for a single gamepad (e.g. one that
+    /// has just hotplugged in) and inserts them into `effects`. No-op when
+    /// `feedback_enabled` is false.
+    fn extend_rumble_effects(
+        &self,
+        gilrs: &mut Gilrs,
+        id: gilrs::GamepadId,
+        effects: &mut RumbleEffects,
+    ) {
+        if !self.feedback_enabled {
+            return;
+        }
+        let Some(controller) = self.resolve_controller(gilrs, id) else {
+            return;
+        };
+        let rumbles: Vec<RumbleCfg> = controller
+            .axes
+            .iter()
+            .filter_map(|a| a.rumble)
+            .chain(controller.buttons.iter().filter_map(|b| b.rumble))
+            .collect();
+
+        for rumble in rumbles {
+            let key = (id, rumble.strength, rumble.duration_millis);
+            if effects.contains_key(&key) {
+                continue;
+            }
+            let built = EffectBuilder::new()
+                .add_effect(BaseEffect {
+                    kind: BaseEffectType::Strong {
+                        magnitude: rumble.strength,
+                    },
+                    scheduling: Replay {
+                        play_for: Ticks::from_ms(rumble.duration_millis),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .gamepads(&[id])
+                .finish(gilrs);
+            if let Ok(effect) = built {
+                effects.insert(key, effect);
+            }
+        }
+    }
+
+    /// Deadline of the soonest pending tap, if any. The main loop should not
+    /// block past this point, or a held button would never transition to its
+    /// `held_key` while the player keeps it down without triggering new events.
+    pub fn next_tap_deadline(&self, state: &RuntimeState) -> Option<SystemTime> {
+        state.pending_taps.values().map(|p| p.deadline).min()
+    }
+
+    /// Deadline of the soonest due repeat, if any. Mirrors `next_tap_deadline`
+    /// so the main loop wakes up in time to re-fire a held repeating key even
+    /// without a new gamepad event.
+    pub fn next_repeat_deadline(&self, state: &RuntimeState) -> Option<SystemTime> {
+        state.repeat_state.values().map(|r| r.next_fire).min()
+    }
+
+    /// Re-fires every repeating key whose deadline has passed as of `now`,
+    /// then reschedules it by its `multi` interval.
+    pub fn flush_due_repeats(&self, now: SystemTime, state: &mut RuntimeState, verbose: bool) {
+        for (key, schedule) in state.repeat_state.iter_mut() {
+            while schedule.next_fire <= now {
+                let _ = simulate(&EventType::KeyPress(*key));
+                if verbose {
+                    println!("\nSimulated repeat key press {:?}", key);
+                }
+                schedule.next_fire += schedule.multi;
+            }
+        }
+    }
+
+    /// Converts any pending tap whose timeout has elapsed as of `now` into a
+    /// held-key press. Called both when a new event arrives and when the main
+    /// loop wakes up purely because a deadline passed with no new event. Only
+    /// the controller that owns the gamepad which raised the tap is
+    /// consulted, so other configured controllers mapping the same
+    /// `gilrs::Button` (e.g. a second preset for a second gamepad) never fire.
+    pub fn flush_expired_taps(
+        &self,
+        now: SystemTime,
+        state: &mut RuntimeState,
+        gilrs: &Gilrs,
+        verbose: bool,
+    ) {
+        let expired: Vec<(gilrs::GamepadId, gilrs::Button)> = state
+            .pending_taps
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired {
+            if let Some(pending) = state.pending_taps.remove(&key) {
+                if let Some(controller) = self.resolve_controller(gilrs, pending.gamepad_id) {
+                    if let Some(mapping) = controller.find_button(pending.button) {
+                        mapping.fire_pending(&pending, state, verbose);
+                    }
+                }
+            }
+        }
+    }
 }
 
 fn read_config(path: &PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
@@ -108,14 +699,44 @@ fn read_config(path: &PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
     Ok(config)
 }
 
-fn key_press_once(key_state: &mut HashMap<Key, bool>, key: Key, verbose: bool) {
+/// Presses `key` if it isn't already down. Returns whether it actually
+/// transitioned, so callers can gate one-shot effects like rumble on it.
+fn key_press_once(key_state: &mut HashMap<Key, bool>, key: Key, verbose: bool) -> bool {
     let is_down = key_state.entry(key).or_insert(false);
     if !*is_down {
         let _ = simulate(&EventType::KeyPress(key));
         if verbose {
             println!("\nSimulated key press {:?}", key);
         }
-        *is_down = true
+        *is_down = true;
+        true
+    } else {
+        false
+    }
+}
+
+/// Plays the pre-built rumble effect for `rumble` on `gamepad_id`, if any.
+fn play_rumble(effects: &RumbleEffects, gamepad_id: gilrs::GamepadId, rumble: Option<RumbleCfg>) {
+    if let Some(cfg) = rumble {
+        if let Some(effect) = effects.get(&(gamepad_id, cfg.strength, cfg.duration_millis)) {
+            let _ = effect.play();
+        }
+    }
+}
+
+/// Arms auto-repeat for `key` the first time it goes down. Later calls while
+/// the key is still held are no-ops so the repeat cadence isn't reset.
+fn schedule_repeat(
+    repeat_state: &mut HashMap<Key, RepeatSchedule>,
+    repeat: Option<RepeatCfg>,
+    key: Key,
+    now: SystemTime,
+) {
+    if let Some(cfg) = repeat {
+        repeat_state.entry(key).or_insert_with(|| RepeatSchedule {
+            next_fire: now + Duration::from_millis(cfg.first),
+            multi: Duration::from_millis(cfg.multi),
+        });
     }
 }
 
@@ -130,6 +751,27 @@ fn key_release_once(key_state: &mut HashMap<Key, bool>, key: Key, verbose: bool)
     }
 }
 
+/// Plays a `KeySequence::Macro` out as a series of discrete taps: each group
+/// is pressed together, then released together, before the next group
+/// starts, so `[["ControlLeft", "KeyX"], ["ControlLeft", "KeyC"]]` fires Ctrl-X
+/// then Ctrl-C rather than holding all three keys down at once.
+fn simulate_macro(groups: &[Vec<Key>], verbose: bool) {
+    for group in groups {
+        for key in group {
+            let _ = simulate(&EventType::KeyPress(*key));
+            if verbose {
+                println!("\nSimulated macro key press {:?}", key);
+            }
+        }
+        for key in group.iter().rev() {
+            let _ = simulate(&EventType::KeyRelease(*key));
+            if verbose {
+                println!("\nSimulated macro key release {:?}", key);
+            }
+        }
+    }
+}
+
 fn main() {
     let matches = command!()
         .arg(
@@ -157,25 +799,46 @@ fn main() {
 
     let verbose = matches.get_count("verbose");
 
-    let mut key_state = HashMap::<Key, bool>::new();
     let mut gilrs = Gilrs::new().unwrap();
+    let mut state = RuntimeState::new(config.build_rumble_effects(&mut gilrs));
 
     loop {
-        while let Some(event) = gilrs.next_event_blocking(None) {
-            if verbose > 1 {
-                println!(
-                    "{:?} New event from {}: {:?}\n",
-                    event.time, event.id, event.event
-                );
-            }
+        let deadline = [
+            config.next_tap_deadline(&state),
+            config.next_repeat_deadline(&state),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let timeout = deadline.map(|deadline| {
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        });
 
-            // match gamepad_id with index of configured a
-            let gamepad_idx = match gilrs.gamepads().zip(0usize..).find(|g| g.0 .0 == event.id) {
-                Some((_, idx)) => idx,
-                _ => continue,
-            };
+        match gilrs.next_event_blocking(timeout) {
+            Some(event) => {
+                if verbose > 1 {
+                    println!(
+                        "{:?} New event from {}: {:?}\n",
+                        event.time, event.id, event.event
+                    );
+                }
 
-            config.handle_event(event, &mut key_state, gamepad_idx, verbose != 0);
+                if matches!(event.event, gilrs::EventType::Connected) {
+                    // A gamepad just (re)connected: build its rumble effects, since
+                    // they could not have been built at startup.
+                    config.extend_rumble_effects(&mut gilrs, event.id, &mut state.rumble_effects);
+                }
+
+                config.handle_event(event, &mut state, &gilrs, verbose != 0);
+            }
+            None => {
+                // Woke up because a pending tap or repeat deadline elapsed, not a new event.
+                let now = SystemTime::now();
+                config.flush_expired_taps(now, &mut state, &gilrs, verbose != 0);
+                config.flush_due_repeats(now, &mut state, verbose != 0);
+            }
         }
     }
 }